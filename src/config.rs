@@ -0,0 +1,64 @@
+use std::path::Path;
+
+use crate::eschaton::{HospitalType, TermVacants};
+
+/// 病院種別1つの表示設定。`HospitalType` の4種別（院内/院外 × 内科/外科）はコンパイル時に固定されており、
+/// ここで設定できるのはその表示絵文字のみ。種別の数や組み合わせ自体を変えるには `HospitalType` の変更が必要
+#[derive(Clone, serde::Deserialize)]
+pub struct CategoryConfig {
+    /// `HospitalType` の variant 名（例: "InnerMedical"）と対応させる
+    pub key: String,
+    pub glyph: String,
+}
+
+/// 名前付きコースと、そのコースが必要とする学期ごとの病院数プロファイル
+#[derive(Clone, serde::Deserialize)]
+pub struct CourseConfig {
+    pub name: String,
+    pub profile: TermVacants,
+}
+
+/// 実行時に読み込む、学期数・病院種別・コース定義・入力ファイルパス
+#[derive(Clone, serde::Deserialize)]
+pub struct Config {
+    /// ローテーションの学期数。`HospitalTableInner`/`Student::selection` はこの値に応じて可変長で構築され、
+    /// 表示ラベルの生成にも使う。`solver::Descriptor` が学期をビットマスク（`u64`）で表現する都合上、
+    /// 64を超える値は扱えない
+    pub term_count: usize,
+    pub categories: Vec<CategoryConfig>,
+    pub courses: Vec<CourseConfig>,
+    pub reserves_path: String,
+    pub students_path: String,
+}
+
+impl Config {
+    /// JSON設定ファイルを読み込む
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let data = std::fs::read(path)?;
+        serde_json::from_slice(&data).map_err(std::io::Error::other)
+    }
+
+    /// 指定の病院種別をちょうど2回必要とするコースを探す（選択履歴からのコース推定に使う）
+    pub fn course_by_doubled(&self, hospital: HospitalType) -> Option<&CourseConfig> {
+        self.courses.iter().find(|c| c.profile.count(hospital) == 2)
+    }
+
+    /// 表示用の絵文字・ラベルを取得する。未設定の場合は `HospitalType` の既定表示にフォールバックする
+    pub fn glyph(&self, hospital: HospitalType) -> String {
+        let key = format!("{hospital:?}");
+        self.categories
+            .iter()
+            .find(|c| c.key == key)
+            .map(|c| c.glyph.clone())
+            .unwrap_or_else(|| hospital.to_string())
+    }
+
+    /// 学期番号（1始まり）の表示ラベル。丸囲み数字を使い、範囲外は数字そのものにフォールバックする
+    pub fn term_label(n: usize) -> String {
+        const CIRCLED: [&str; 6] = ["①", "②", "③", "④", "⑤", "⑥"];
+        CIRCLED
+            .get(n - 1)
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| n.to_string())
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::HashSet;
+
+use crate::{
+    config::Config,
+    eschaton::{HospitalTable, HospitalType, Student},
+};
+
+const HOSPITAL_TYPES: [HospitalType; 4] = [
+    HospitalType::InnerMedical,
+    HospitalType::InnerSurgical,
+    HospitalType::OuterSurgical,
+    HospitalType::OuterMedical,
+];
+
+/// Hall の結婚定理違反の内訳: どの学生集合が、どの (学期, 病院種別) の枠不足で詰むか
+pub struct Deficiency {
+    pub students: Vec<String>,
+    pub bottleneck: Vec<(usize, HospitalType)>,
+    pub shortage: usize,
+}
+
+/// 需要側ユニット: ある学生の、ある病院種別の1単位分の需要
+#[derive(Clone, Copy)]
+struct DemandUnit {
+    student: usize,
+    hospital: HospitalType,
+}
+
+/// 供給側ユニット: ある学期の、ある病院種別の1枠分の供給
+#[derive(Clone, Copy)]
+struct SupplyUnit {
+    term: usize,
+    hospital: HospitalType,
+}
+
+/// 需要が供給を上回っている学生集合とその原因となる枠を特定する。
+///
+/// `table` と `students` の組から需要・供給の二部グラフを構築し、最大二部マッチングを求める。
+/// 需要を取りこぼす場合は未マッチの需要から交互パスで到達できるノードを集め（König の構成）、
+/// 到達した学生集合とその需要が依存する枠を Hall 違反の witness として返す。
+pub fn find_deficiency(
+    table: &HospitalTable,
+    students: &[Student],
+    config: &Config,
+) -> Option<Deficiency> {
+    let mut demands: Vec<DemandUnit> = Vec::new();
+    for (i, student) in students.iter().enumerate() {
+        if student.done() || student.course(config).is_none() {
+            // course() が None の学生は required_hospitals() が infinite() を返し無制約なのでスキップする
+            continue;
+        }
+        let required = student.required_hospitals(config);
+        for &hospital in &HOSPITAL_TYPES {
+            for _ in 0..required.count(hospital) {
+                demands.push(DemandUnit { student: i, hospital });
+            }
+        }
+    }
+    if demands.is_empty() {
+        return None;
+    }
+
+    let mut supplies: Vec<SupplyUnit> = Vec::new();
+    for (term, slot) in table.as_inner().iter().enumerate() {
+        for &hospital in &HOSPITAL_TYPES {
+            for _ in 0..slot.count(hospital) {
+                supplies.push(SupplyUnit { term, hospital });
+            }
+        }
+    }
+
+    let selectable_terms: Vec<Vec<usize>> = students
+        .iter()
+        .map(|s| s.selectable_terms().collect())
+        .collect();
+    let adjacency: Vec<Vec<usize>> = demands
+        .iter()
+        .map(|demand| {
+            supplies
+                .iter()
+                .enumerate()
+                .filter(|(_, supply)| {
+                    supply.hospital == demand.hospital
+                        && selectable_terms[demand.student].contains(&supply.term)
+                })
+                .map(|(j, _)| j)
+                .collect()
+        })
+        .collect();
+
+    let mut match_supply: Vec<Option<usize>> = vec![None; supplies.len()];
+    let mut matched_demand = vec![false; demands.len()];
+    for (d, matched) in matched_demand.iter_mut().enumerate() {
+        let mut visited = vec![false; supplies.len()];
+        if try_augment(d, &adjacency, &mut visited, &mut match_supply) {
+            *matched = true;
+        }
+    }
+
+    if matched_demand.iter().all(|&m| m) {
+        // 全ての需要ユニットが枠にマッチした。このグラフは1人の学生が同じ学期の枠を複数使う
+        // 組合せを除外しないため、実際に割当が存在することまでは保証しない
+        return None;
+    }
+
+    let mut reached_demand = vec![false; demands.len()];
+    let mut reached_supply = vec![false; supplies.len()];
+    for (d, &matched) in matched_demand.iter().enumerate() {
+        if !matched {
+            alternate(
+                d,
+                &adjacency,
+                &match_supply,
+                &mut reached_demand,
+                &mut reached_supply,
+            );
+        }
+    }
+
+    let mut students_out: Vec<String> = reached_demand
+        .iter()
+        .enumerate()
+        .filter(|&(_, &reached)| reached)
+        .map(|(d, _)| demands[d].student)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(|i| students[i].get_name().to_string())
+        .collect();
+    students_out.sort();
+
+    let mut bottleneck: Vec<(usize, HospitalType)> = reached_supply
+        .iter()
+        .enumerate()
+        .filter(|&(_, &reached)| reached)
+        .map(|(s, _)| (supplies[s].term, supplies[s].hospital))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    bottleneck.sort_by_key(|&(term, h)| (term, h.index()));
+
+    let shortage = reached_demand.iter().filter(|&&r| r).count()
+        - reached_supply.iter().filter(|&&r| r).count();
+
+    Some(Deficiency {
+        students: students_out,
+        bottleneck,
+        shortage,
+    })
+}
+
+/// Kuhn のアルゴリズムによる増加路探索
+fn try_augment(
+    d: usize,
+    adjacency: &[Vec<usize>],
+    visited: &mut [bool],
+    match_supply: &mut [Option<usize>],
+) -> bool {
+    for &s in &adjacency[d] {
+        if visited[s] {
+            continue;
+        }
+        visited[s] = true;
+        let can_take = match match_supply[s] {
+            None => true,
+            Some(prev) => try_augment(prev, adjacency, visited, match_supply),
+        };
+        if can_take {
+            match_supply[s] = Some(d);
+            return true;
+        }
+    }
+    false
+}
+
+/// 未マッチの需要ユニットから交互パスで到達可能なノードを集める
+fn alternate(
+    d: usize,
+    adjacency: &[Vec<usize>],
+    match_supply: &[Option<usize>],
+    reached_demand: &mut [bool],
+    reached_supply: &mut [bool],
+) {
+    if reached_demand[d] {
+        return;
+    }
+    reached_demand[d] = true;
+    for &s in &adjacency[d] {
+        if reached_supply[s] {
+            continue;
+        }
+        reached_supply[s] = true;
+        if let Some(next) = match_supply[s] {
+            alternate(next, adjacency, match_supply, reached_demand, reached_supply);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CourseConfig;
+    use crate::eschaton::{InitStudentOption, TermVacants};
+
+    /// InnerMedical を2回選ぶとコース判定が付き、OuterMedical があと1つ必要になる学生を作る
+    fn enrolled_student(table: &mut HospitalTable, name: &str) -> Student {
+        table.init_student(InitStudentOption {
+            name: name.to_string(),
+            terms: vec![
+                Some(HospitalType::InnerMedical),
+                Some(HospitalType::InnerMedical),
+                None,
+                None,
+                None,
+                None,
+            ],
+        })
+    }
+
+    fn test_config() -> Config {
+        Config {
+            term_count: 6,
+            categories: Vec::new(),
+            courses: vec![CourseConfig {
+                name: "Test".to_string(),
+                profile: TermVacants::new(2, 0, 0, 1),
+            }],
+            reserves_path: String::new(),
+            students_path: String::new(),
+        }
+    }
+
+    /// InnerMedical を2回選び、OuterMedical を2つ必要とする学生を作る
+    fn enrolled_student_needing_two(table: &mut HospitalTable, name: &str) -> Student {
+        table.init_student(InitStudentOption {
+            name: name.to_string(),
+            terms: vec![
+                Some(HospitalType::InnerMedical),
+                Some(HospitalType::InnerMedical),
+                None,
+                None,
+                None,
+                None,
+            ],
+        })
+    }
+
+    fn test_config_needing_two() -> Config {
+        Config {
+            term_count: 6,
+            categories: Vec::new(),
+            courses: vec![CourseConfig {
+                name: "Test".to_string(),
+                profile: TermVacants::new(2, 0, 0, 2),
+            }],
+            reserves_path: String::new(),
+            students_path: String::new(),
+        }
+    }
+
+    #[test]
+    fn no_deficiency_when_demand_fits_in_supply() {
+        let config = test_config();
+        let mut table = HospitalTable::new(
+            (0..6)
+                .map(|i| match i {
+                    0 | 1 => TermVacants::new(1, 0, 0, 0),
+                    2 => TermVacants::new(0, 0, 0, 1),
+                    _ => TermVacants::zero(),
+                })
+                .collect(),
+        );
+        let student = enrolled_student(&mut table, "Alice");
+        assert!(find_deficiency(&table, &[student], &config).is_none());
+    }
+
+    #[test]
+    fn reports_hall_violation_when_two_students_compete_for_one_slot() {
+        let config = test_config();
+        let mut table = HospitalTable::new(
+            (0..6)
+                .map(|i| match i {
+                    0 | 1 => TermVacants::new(2, 0, 0, 0),
+                    2 => TermVacants::new(0, 0, 0, 1),
+                    _ => TermVacants::zero(),
+                })
+                .collect(),
+        );
+        let carol = enrolled_student(&mut table, "Carol");
+        let dave = enrolled_student(&mut table, "Dave");
+
+        let deficiency = find_deficiency(&table, &[carol, dave], &config)
+            .expect("two students sharing one OuterMedical slot must be a Hall violation");
+        assert_eq!(deficiency.shortage, 1);
+        assert_eq!(deficiency.students, vec!["Carol".to_string(), "Dave".to_string()]);
+        assert_eq!(deficiency.bottleneck, vec![(2, HospitalType::OuterMedical)]);
+    }
+
+    #[test]
+    fn bottleneck_is_sorted_even_with_multiple_cells() {
+        // HashSet の反復順に依存すると壊れることを確認するため、複数のボトルネック枠が
+        // 同時に生じる（= 複数回実行しても常に同じ順序で返る）ケースを用意する
+        let config = test_config_needing_two();
+        let mut table = HospitalTable::new(
+            (0..6)
+                .map(|i| match i {
+                    0 | 1 => TermVacants::new(2, 0, 0, 0),
+                    3..=5 => TermVacants::new(0, 0, 0, 1),
+                    _ => TermVacants::zero(),
+                })
+                .collect(),
+        );
+        let carol = enrolled_student_needing_two(&mut table, "Carol");
+        let dave = enrolled_student_needing_two(&mut table, "Dave");
+
+        let deficiency = find_deficiency(&table, &[carol, dave], &config)
+            .expect("two students needing two OuterMedical slots each, with only three available, must be a Hall violation");
+        assert_eq!(
+            deficiency.bottleneck,
+            vec![
+                (3, HospitalType::OuterMedical),
+                (4, HospitalType::OuterMedical),
+                (5, HospitalType::OuterMedical),
+            ]
+        );
+    }
+}
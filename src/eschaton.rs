@@ -5,10 +5,13 @@ use std::{
     str::FromStr,
 };
 
-use serde_with::{DeserializeFromStr, NoneAsEmptyString, serde_as};
+use rand::seq::SliceRandom;
+use serde_with::DeserializeFromStr;
+
+use crate::config::{Config, CourseConfig};
 
 // 院外、院内、外科、内科はそれぞれ3つずつ選択する必要がある
-#[derive(Eq, PartialEq, Hash, Clone, Copy, DeserializeFromStr)]
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug, DeserializeFromStr)]
 pub enum HospitalType {
     InnerMedical,
     InnerSurgical,
@@ -29,6 +32,18 @@ impl FromStr for HospitalType {
     }
 }
 
+impl HospitalType {
+    /// 配列に格納するためのインデックス
+    pub fn index(&self) -> usize {
+        match self {
+            HospitalType::InnerMedical => 0,
+            HospitalType::InnerSurgical => 1,
+            HospitalType::OuterSurgical => 2,
+            HospitalType::OuterMedical => 3,
+        }
+    }
+}
+
 impl Display for HospitalType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -44,13 +59,6 @@ impl Display for HospitalType {
     }
 }
 
-pub enum Course {
-    /// 院外外科1、院外内科2、院内外科2、院内内科1
-    Eschaton,
-    /// 院外外科2、院外内科1、院内外科1、院内内科2
-    Avoidance,
-}
-
 #[derive(Clone, serde::Deserialize, serde::Serialize)]
 pub struct TermVacants {
     pub inner_medical: usize,
@@ -70,6 +78,17 @@ impl SubAssign<HospitalType> for TermVacants {
     }
 }
 
+impl AddAssign<HospitalType> for TermVacants {
+    fn add_assign(&mut self, rhs: HospitalType) {
+        match rhs {
+            HospitalType::InnerMedical => self.inner_medical += 1,
+            HospitalType::InnerSurgical => self.inner_surgical += 1,
+            HospitalType::OuterMedical => self.outer_medical += 1,
+            HospitalType::OuterSurgical => self.outer_surgical += 1,
+        }
+    }
+}
+
 impl TermVacants {
     pub fn len(&self) -> usize {
         self.inner_medical + self.inner_surgical + self.outer_medical + self.outer_surgical
@@ -134,48 +153,18 @@ impl AddAssign<&Self> for TermVacants {
     }
 }
 
-impl From<&Course> for TermVacants {
-    fn from(value: &Course) -> Self {
-        match value {
-            Course::Eschaton => TermVacants {
-                inner_medical: 1,
-                inner_surgical: 2,
-                outer_medical: 2,
-                outer_surgical: 1,
-            },
-            Course::Avoidance => TermVacants {
-                inner_medical: 2,
-                inner_surgical: 1,
-                outer_medical: 1,
-                outer_surgical: 2,
-            },
-        }
-    }
-}
-
 /// 学生
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct Student {
     name: String,
-    selection: [Option<HospitalType>; 6],
+    selection: Vec<Option<HospitalType>>,
 }
 
-#[serde_as]
-#[derive(serde::Deserialize)]
+/// CSVの1行分の入力。学期数は `config.term_count` によって可変なので、
+/// 固定長フィールドの serde 導出ではなく学生データ読み込み側で `terms` を組み立てる
 pub struct InitStudentOption {
     pub name: String,
-    #[serde_as(as = "NoneAsEmptyString")]
-    pub term1: Option<HospitalType>,
-    #[serde_as(as = "NoneAsEmptyString")]
-    pub term2: Option<HospitalType>,
-    #[serde_as(as = "NoneAsEmptyString")]
-    pub term3: Option<HospitalType>,
-    #[serde_as(as = "NoneAsEmptyString")]
-    pub term4: Option<HospitalType>,
-    #[serde_as(as = "NoneAsEmptyString")]
-    pub term5: Option<HospitalType>,
-    #[serde_as(as = "NoneAsEmptyString")]
-    pub term6: Option<HospitalType>,
+    pub terms: Vec<Option<HospitalType>>,
 }
 
 impl Student {
@@ -188,35 +177,30 @@ impl Student {
         self.name
     }
     /// 選択状況を取得
-    pub fn get_selection(&self) -> &[Option<HospitalType>; 6] {
+    pub fn get_selection(&self) -> &[Option<HospitalType>] {
         &self.selection
     }
-    /// コースが推定できる場合は返す
-    pub fn course(&self) -> Option<Course> {
+    /// 既に2回選んでいる病院種別から、設定ファイルで定義されたコースを推定する
+    pub fn course<'a>(&self, config: &'a Config) -> Option<&'a CourseConfig> {
         let mut already_shown = HashSet::new();
         for h in self.selection.iter().filter_map(|x| x.as_ref()) {
             if !already_shown.insert(h) {
-                return Some(match h {
-                    HospitalType::InnerMedical => Course::Avoidance,
-                    HospitalType::InnerSurgical => Course::Eschaton,
-                    HospitalType::OuterMedical => Course::Eschaton,
-                    HospitalType::OuterSurgical => Course::Avoidance,
-                });
+                return config.course_by_doubled(*h);
             }
         }
         None
     }
     /// 選択可能な学期のインデックスを返す
-    pub fn selectable_terms(&self) -> impl Iterator<Item = usize> {
+    pub fn selectable_terms(&self) -> impl Iterator<Item = usize> + '_ {
         self.selection
             .iter()
             .enumerate()
             .filter_map(|(i, x)| if x.is_none() { Some(i) } else { None })
     }
     /// 現時点で残り必要な病院の数を返す
-    fn required_hospitals(&self) -> TermVacants {
-        if let Some(course) = self.course() {
-            let mut slot = TermVacants::from(&course);
+    pub(crate) fn required_hospitals(&self, config: &Config) -> TermVacants {
+        if let Some(course) = self.course(config) {
+            let mut slot = course.profile.clone();
             for selection in self.selection.iter().flatten() {
                 slot -= *selection;
             }
@@ -229,18 +213,69 @@ impl Student {
     pub fn done(&self) -> bool {
         self.selection.iter().all(|x| x.is_some())
     }
+    /// 探索エンジンから学期に病院種別を割り当てる
+    pub(crate) fn assign(&mut self, term: usize, hospital: HospitalType) {
+        self.selection[term] = Some(hospital);
+    }
+    /// 探索エンジンから割当を取り消す
+    pub(crate) fn unassign(&mut self, term: usize) {
+        self.selection[term] = None;
+    }
 }
 
-pub type HospitalTableInner = [TermVacants; 6];
+/// 各ラウンドの学生の処理順序と割当先の選び方を決める戦略
+#[derive(Clone, Copy)]
+pub enum SelectionStrategy {
+    /// 残っている枠から一様ランダムに選ぶ（従来の挙動）
+    Uniform,
+    /// 選択肢が最も少ない学生を優先し、その中でも最も枯渇している枠を選ぶ
+    MostConstrainedFirst,
+    /// 残数が少ない枠ほど選ばれやすいよう重み付けしたランダム選択
+    Weighted,
+}
+
+impl FromStr for SelectionStrategy {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "uniform" => Ok(SelectionStrategy::Uniform),
+            "most-constrained-first" | "mcf" => Ok(SelectionStrategy::MostConstrainedFirst),
+            "weighted" => Ok(SelectionStrategy::Weighted),
+            _ => Err("Could not parse the &str as SelectionStrategy"),
+        }
+    }
+}
+
+impl SelectionStrategy {
+    /// このラウンドで学生を処理する順序を決める
+    pub fn order_students(
+        &self,
+        students: &mut [Student],
+        table: &HospitalTable,
+        config: &Config,
+        mut rng: impl rand::Rng,
+    ) {
+        match self {
+            SelectionStrategy::MostConstrainedFirst => {
+                students.sort_by_key(|student| (table & (student, config)).len());
+            }
+            SelectionStrategy::Uniform | SelectionStrategy::Weighted => {
+                students.shuffle(&mut rng);
+            }
+        }
+    }
+}
+
+pub type HospitalTableInner = Vec<TermVacants>;
 
 #[derive(Clone)]
 pub struct HospitalTable(HospitalTableInner);
 
-impl BitAnd<&Student> for &HospitalTable {
+impl BitAnd<(&Student, &Config)> for &HospitalTable {
     type Output = HospitalTable;
-    fn bitand(self, rhs: &Student) -> Self::Output {
-        let mut hospitals: HospitalTableInner = core::array::from_fn(|_| TermVacants::zero());
-        let slots = rhs.required_hospitals();
+    fn bitand(self, (rhs, config): (&Student, &Config)) -> Self::Output {
+        let mut hospitals: HospitalTableInner = vec![TermVacants::zero(); self.0.len()];
+        let slots = rhs.required_hospitals(config);
         for term in rhs.selectable_terms() {
             hospitals[term] = self.0[term].clone() & &slots;
         }
@@ -265,7 +300,7 @@ impl HospitalTable {
         self.0.iter().map(TermVacants::len).sum()
     }
     fn index(&self, mut i: usize) -> Option<(usize, HospitalType)> {
-        let sizes: [usize; 6] = self.0.clone().map(|slot| slot.len());
+        let sizes: Vec<usize> = self.0.iter().map(|slot| slot.len()).collect();
         for (term, &size) in sizes.iter().enumerate() {
             if i < size {
                 let slot = &self.0[term];
@@ -290,39 +325,88 @@ impl HospitalTable {
         // If we reach here, i > self.len() or self.is_empty()
         None
     }
-    /// ランダムに学生を選択する
-    pub fn random_select(
+    /// 病院種別と残数を列挙する
+    pub(crate) fn iter_choices(&self) -> impl Iterator<Item = (usize, HospitalType, usize)> + '_ {
+        self.0.iter().enumerate().flat_map(|(term, slot)| {
+            [
+                HospitalType::InnerMedical,
+                HospitalType::InnerSurgical,
+                HospitalType::OuterSurgical,
+                HospitalType::OuterMedical,
+            ]
+            .into_iter()
+            .filter_map(move |hospital| {
+                let count = slot.count(hospital);
+                (count > 0).then_some((term, hospital, count))
+            })
+        })
+    }
+    /// `strategy` に従って学生を選択する
+    pub fn select(
         &mut self,
         student: &mut Student,
+        strategy: &SelectionStrategy,
+        config: &Config,
         mut rng: impl rand::Rng,
     ) -> Result<(), ()> {
-        let choices = &*self & student;
+        let choices = &*self & (&*student, config);
         if choices.is_empty() {
             return Err(());
         }
-        let rnd_index = { rng.random_range(0..choices.len()) };
-        let (term, hospital) = choices.index(rnd_index).ok_or(())?;
+        let (term, hospital) = match strategy {
+            SelectionStrategy::Uniform => {
+                let rnd_index = rng.random_range(0..choices.len());
+                choices.index(rnd_index).ok_or(())?
+            }
+            SelectionStrategy::MostConstrainedFirst => choices
+                .iter_choices()
+                .min_by_key(|&(term, hospital, _)| self.0[term].count(hospital))
+                .map(|(term, hospital, _)| (term, hospital))
+                .ok_or(())?,
+            SelectionStrategy::Weighted => {
+                // 残数が少ない枠ほど埋まりやすいよう、重みを残数の逆数にする
+                let weights: Vec<_> = choices
+                    .iter_choices()
+                    .map(|(term, hospital, _)| {
+                        (term, hospital, 1.0 / self.0[term].count(hospital) as f64)
+                    })
+                    .collect();
+                let total: f64 = weights.iter().map(|&(_, _, w)| w).sum();
+                let mut pick = rng.random_range(0.0..total);
+                // choices is non-empty (checked above), so weights has a last entry. Falling
+                // back to it instead of erroring out keeps floating-point residual error across
+                // the sum/walk passes from ever turning a real, available slot into a spurious
+                // failed trial.
+                let (last_term, last_hospital, _) =
+                    *weights.last().expect("choices is non-empty");
+                weights
+                    .into_iter()
+                    .find_map(|(term, hospital, w)| {
+                        if pick < w {
+                            Some((term, hospital))
+                        } else {
+                            pick -= w;
+                            None
+                        }
+                    })
+                    .unwrap_or((last_term, last_hospital))
+            }
+        };
         self.0[term] -= hospital;
         student.selection[term] = Some(hospital);
         Ok(())
     }
     pub fn init_student(&mut self, student: InitStudentOption) -> Student {
-        let InitStudentOption {
-            name,
-            term1,
-            term2,
-            term3,
-            term4,
-            term5,
-            term6,
-        } = student;
-        let selection = [term1, term2, term3, term4, term5, term6];
+        let InitStudentOption { name, terms } = student;
 
-        for (term, hospital) in selection.iter().enumerate() {
+        for (term, hospital) in terms.iter().enumerate() {
             if let Some(hospital) = hospital {
                 self.0[term] -= *hospital;
             }
         }
-        Student { name, selection }
+        Student {
+            name,
+            selection: terms,
+        }
     }
 }
@@ -1,36 +1,42 @@
 use std::{
-    collections::HashMap, iter::once, mem::MaybeUninit, ops::AddAssign, sync::Arc,
-    thread::available_parallelism,
+    collections::HashMap, iter::once, ops::AddAssign, sync::Arc, thread::available_parallelism,
 };
 
-use rand::seq::SliceRandom;
 use tabled::settings::Style;
 
+mod config;
+mod diagnostics;
 mod eschaton;
+mod solver;
+
+const HOSPITAL_TYPES: [eschaton::HospitalType; 4] = [
+    eschaton::HospitalType::InnerMedical,
+    eschaton::HospitalType::InnerSurgical,
+    eschaton::HospitalType::OuterMedical,
+    eschaton::HospitalType::OuterSurgical,
+];
 
 struct Report<'a> {
     state: &'a State,
     student: &'a Option<&'a eschaton::Student>,
-    db: &'a eschaton::Database,
+    db: &'a eschaton::HospitalTable,
+    config: &'a config::Config,
 }
 
 impl std::fmt::Display for Report<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let term_labels: Vec<String> = (1..=self.config.term_count)
+            .map(config::Config::term_label)
+            .collect();
         let mut builder = tabled::builder::Builder::new();
-        builder.push_record(["LAST REPORT", "①", "②", "③", "④", "⑤", "⑥"]);
-        let slots = self.db.get_slots();
-        builder.push_record(
-            once("院内内科".to_string()).chain(slots.iter().map(|s| s.inner_medical.to_string())),
-        );
-        builder.push_record(
-            once("院内外科".to_string()).chain(slots.iter().map(|s| s.inner_surgical.to_string())),
-        );
-        builder.push_record(
-            once("院外内科".to_string()).chain(slots.iter().map(|s| s.outer_medical.to_string())),
-        );
-        builder.push_record(
-            once("院外外科".to_string()).chain(slots.iter().map(|s| s.outer_surgical.to_string())),
-        );
+        builder.push_record(once("LAST REPORT".to_string()).chain(term_labels.iter().cloned()));
+        let slots = self.db.as_inner();
+        for &hospital in &HOSPITAL_TYPES {
+            builder.push_record(
+                once(self.config.glyph(hospital))
+                    .chain(slots.iter().map(|s| s.count(hospital).to_string())),
+            );
+        }
         if let Some(student) = &self.student {
             let student_name = {
                 let name = student.get_name();
@@ -54,6 +60,31 @@ impl std::fmt::Display for Report<'_> {
         std::fmt::Display::fmt(table.with(Style::modern()), f)?;
         writeln!(f)?;
 
+        if let Some(student) = &self.student {
+            if let Some(histogram) = self.state.histograms.get(student.get_name()) {
+                // 各学期は毎回の成功試行で必ず1つ割り当てられるので、どの学期の合計も試行回数に一致する
+                let trials: usize = histogram[0].iter().sum();
+                let mut builder = tabled::builder::Builder::new();
+                builder.push_record(
+                    once("DISTRIBUTION".to_string()).chain(term_labels.iter().cloned()),
+                );
+                for &hospital in &HOSPITAL_TYPES {
+                    builder.push_record(once(self.config.glyph(hospital)).chain(histogram.iter().map(
+                        |term| {
+                            if trials == 0 {
+                                "  -  ".to_string()
+                            } else {
+                                format!("{:.1} %", 100.0 * term[hospital.index()] as f64 / trials as f64)
+                            }
+                        },
+                    )));
+                }
+                let mut table = builder.build();
+                std::fmt::Display::fmt(table.with(Style::modern()), f)?;
+                writeln!(f)?;
+            }
+        }
+
         writeln!(
             f,
             "TRIAL: {}, SUCCESS: {} ({:.3} %)",
@@ -80,143 +111,229 @@ impl std::fmt::Display for Report<'_> {
     }
 }
 
+/// 学期 × 病院種別 の割当回数
+type TermHistogram = Vec<[usize; 4]>;
+
+/// 1回の試行の結果: 最後まで割り当てられなかった学生と、割り当てきれた学生
+struct TrialResult {
+    failed: Vec<eschaton::Student>,
+    completed: Vec<eschaton::Student>,
+    /// 新規にヒストグラムを作る際のサイズ決定に使う学期数
+    term_count: usize,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Clone, Default)]
 struct State {
     pub count: usize,
     pub success: usize,
     pub fails: HashMap<String, usize>,
+    /// 成功した試行における、学生ごとの学期×病院種別の割当回数
+    pub histograms: HashMap<String, TermHistogram>,
 }
 
-impl AddAssign<&Self> for State {
-    fn add_assign(&mut self, rhs: &Self) {
-        self.count += rhs.count;
-        for (name, count) in &rhs.fails {
-            self.fails
-                .entry(name.clone())
-                .and_modify(|c| *c += count)
-                .or_insert(*count);
-        }
-    }
-}
-
-impl AddAssign<Vec<eschaton::Student>> for State {
-    fn add_assign(&mut self, rhs: Vec<eschaton::Student>) {
+impl AddAssign<TrialResult> for State {
+    fn add_assign(&mut self, rhs: TrialResult) {
         self.count += 1;
-        if rhs.is_empty() {
+        let trial_succeeded = rhs.failed.is_empty();
+        if trial_succeeded {
             self.success += 1;
         }
-        for student in rhs {
+        for student in rhs.failed {
             self.fails
                 .entry(student.into_name())
                 .and_modify(|c| *c += 1)
                 .or_insert(1);
         }
+        // 試行全体が成功した場合のみヒストグラムに積む。一部の学生が詰んだ試行は母集団が偏るため含めない
+        if trial_succeeded {
+            for student in rhs.completed {
+                let histogram = self
+                    .histograms
+                    .entry(student.get_name().to_string())
+                    .or_insert_with(|| vec![[0; 4]; rhs.term_count]);
+                for (term, hospital) in student.get_selection().iter().enumerate() {
+                    if let Some(hospital) = hospital {
+                        histogram[term][hospital.index()] += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 既存のチェックポイントを読み込む。CBOR を優先し、無ければ JSON にフォールバックする
+async fn load_state() -> State {
+    if let Ok(data) = tokio::fs::read("state.cbor").await {
+        if let Ok(state) = ciborium::from_reader(data.as_slice()) {
+            return state;
+        }
+    }
+    if let Ok(data) = tokio::fs::read("state.json").await {
+        if let Ok(state) = serde_json::from_slice(&data) {
+            return state;
+        }
     }
+    State::default()
+}
+
+/// CBOR を正として書き出し、閲覧用に JSON も併せてエクスポートする
+async fn save_state(state: &State) {
+    let mut cbor = Vec::new();
+    ciborium::into_writer(state, &mut cbor).expect("Failed to encode state as CBOR");
+    tokio::fs::write("state.cbor", cbor)
+        .await
+        .expect("Failed to write state.cbor");
+    tokio::fs::write(
+        "state.json",
+        serde_json::to_vec_pretty(state).expect("Failed to encode state as JSON"),
+    )
+    .await
+    .expect("Failed to write state.json");
 }
 
 #[tokio::main]
 async fn main() {
-    let (tx, mut rx) =
-        tokio::sync::mpsc::channel::<(Vec<eschaton::Student>, eschaton::Database)>(4096);
+    let config_path = std::env::args()
+        .nth(1)
+        .expect("Usage: eschaton <config.json> [strategy]");
+    let config = Arc::new(config::Config::load(config_path).expect("Failed to load config file"));
+    assert!(
+        config.term_count <= 64,
+        "term_count is {}, but solver::Descriptor tracks selectable terms in a u64 bitmask \
+         and so cannot exceed 64",
+        config.term_count,
+    );
+    let strategy = std::env::args()
+        .nth(2)
+        .map(|s| s.parse().expect("Unknown selection strategy"))
+        .unwrap_or(eschaton::SelectionStrategy::Uniform);
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<(TrialResult, eschaton::HospitalTable)>(4096);
+    let report_config = config.clone();
     tokio::spawn(async move {
         let stdout_duration = std::time::Duration::from_millis(1000 / 5);
         let save_duration = std::time::Duration::from_secs(5);
 
-        let mut state = {
-            if tokio::fs::metadata("state.json").await.is_ok() {
-                let data = tokio::fs::read("state.json").await.unwrap();
-                serde_json::from_slice::<State>(&data).unwrap()
-            } else {
-                Default::default()
-            }
-        };
+        let mut state = load_state().await;
         let mut last_shown = tokio::time::Instant::now();
         let mut last_saved = tokio::time::Instant::now();
         loop {
-            if let Some((students, db)) = rx.recv().await {
+            if let Some((result, db)) = rx.recv().await {
                 if last_shown.elapsed() > stdout_duration {
                     last_shown = tokio::time::Instant::now();
                     if last_saved.elapsed() > save_duration {
                         last_saved = tokio::time::Instant::now();
-                        tokio::fs::write("state.json", serde_json::to_vec_pretty(&state).unwrap())
-                            .await
-                            .unwrap();
+                        save_state(&state).await;
                     }
                     let report = Report {
                         state: &state,
-                        student: &students.first(),
+                        student: &result.failed.first(),
                         db: &db,
+                        config: &report_config,
                     };
                     println!("{report}");
                 }
-                state += students;
+                state += result;
             } else {
-                tokio::fs::write("state.json", serde_json::to_vec_pretty(&state).unwrap())
-                    .await
-                    .unwrap();
-                println!("Successfully saved state.json");
+                save_state(&state).await;
+                println!("Successfully saved checkpoint");
             }
         }
     });
-    let mut reserves = csv::Reader::from_reader(include_bytes!("./reserves.csv").as_slice());
-    let mut maybeuninit: [MaybeUninit<eschaton::HospitalSlots>; 6] =
-        std::array::from_fn(|_| MaybeUninit::uninit());
-    for (i, row) in reserves.deserialize().enumerate() {
-        maybeuninit
-            .get_mut(i)
-            .expect("Too many rows in reserves.csv")
-            .write(row.expect("Failed to parse reserves.csv"));
-    }
+    let mut reserves =
+        csv::Reader::from_reader(std::fs::File::open(&config.reserves_path).unwrap_or_else(
+            |e| panic!("Failed to open reserves file {}: {e}", config.reserves_path),
+        ));
+    let reserves: Vec<eschaton::TermVacants> = reserves
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .expect("Failed to parse reserves file");
+    assert_eq!(
+        reserves.len(),
+        config.term_count,
+        "reserves file has {} row(s), but config.term_count is {}",
+        reserves.len(),
+        config.term_count,
+    );
     let (db, students) = {
-        let mut db = eschaton::Database::new(unsafe {
-            std::mem::transmute::<
-                [MaybeUninit<eschaton::HospitalSlots>; 6],
-                [eschaton::HospitalSlots; 6],
-            >(maybeuninit)
-        });
+        let mut db = eschaton::HospitalTable::new(reserves);
+        let students_file = std::fs::File::open(&config.students_path)
+            .unwrap_or_else(|e| panic!("Failed to open students file {}: {e}", config.students_path));
         let students = Arc::new(
-            csv::Reader::from_reader(include_bytes!("./students.csv").as_slice())
-                .deserialize::<eschaton::StudentRecord>()
-                .collect::<Result<Vec<_>, _>>()
-                .unwrap()
-                .into_iter()
-                .map(|s| {
-                    let (name, selection) = s.extract();
-                    db.new_student(name, selection)
+            csv::Reader::from_reader(students_file)
+                .records()
+                .map(|record| {
+                    let record = record.expect("Failed to parse students file");
+                    let name = record
+                        .get(0)
+                        .expect("students file row is missing the name column")
+                        .to_string();
+                    let terms = (0..config.term_count)
+                        .map(|term| match record.get(term + 1) {
+                            Some(cell) if !cell.trim().is_empty() => Some(
+                                cell.parse()
+                                    .expect("Failed to parse hospital type in students file"),
+                            ),
+                            _ => None,
+                        })
+                        .collect();
+                    eschaton::InitStudentOption { name, terms }
                 })
+                .map(|s| db.init_student(s))
                 .collect::<Vec<_>>(),
         );
         (Arc::new(db), students)
     };
+    match solver::Solver::new().solve(&db, &students, &config) {
+        Some(_) => println!("Exact solver: a full assignment exists for the current data."),
+        None => {
+            println!("Exact solver: no full assignment exists for the current data.");
+            if let Some(deficiency) = diagnostics::find_deficiency(&db, &students, &config) {
+                println!(
+                    "Hall deficiency: students {:?} are short by {} unit(s) in cells {:?}",
+                    deficiency.students, deficiency.shortage, deficiency.bottleneck
+                );
+            }
+        }
+    }
     for _ in 0..available_parallelism().unwrap().into() {
         let tx = tx.clone();
         let db = db.clone();
         let students = students.clone();
+        let config = config.clone();
         tokio::spawn(async move {
             loop {
-                let mut db: eschaton::Database = db.as_ref().clone();
-                let result: (Vec<_>, eschaton::Database) = {
+                let mut db: eschaton::HospitalTable = db.as_ref().clone();
+                let result: (TrialResult, eschaton::HospitalTable) = {
                     let mut rng = rand::rng();
                     let mut students: Vec<eschaton::Student> = {
                         let mut s = students.as_ref().clone();
-                        s.shuffle(&mut rng);
+                        strategy.order_students(&mut s, &db, &config, &mut rng);
                         s
                     }
                     .clone();
-                    let mut eschatons = Vec::new();
+                    let mut failed = Vec::new();
+                    let mut completed = Vec::new();
                     'game: loop {
                         let mut undone_students = Vec::new();
                         for mut student in students {
                             if student.done() {
-                                continue;
-                            } else if db.random_select(&mut student, &mut rng).is_err() {
-                                eschatons.push(student);
+                                completed.push(student);
+                            } else if db.select(&mut student, &strategy, &config, &mut rng).is_err() {
+                                failed.push(student);
                             } else {
                                 undone_students.push(student);
                             }
                         }
                         if undone_students.is_empty() {
-                            break 'game (eschatons, db);
+                            break 'game (
+                                TrialResult {
+                                    failed,
+                                    completed,
+                                    term_count: config.term_count,
+                                },
+                                db,
+                            );
                         }
                         students = undone_students;
                     }
@@ -0,0 +1,254 @@
+use std::{
+    collections::{HashSet, hash_map::DefaultHasher},
+    hash::{Hash, Hasher},
+};
+
+use crate::{
+    config::Config,
+    eschaton::{HospitalTable, HospitalTableInner, HospitalType, Student},
+};
+
+/// 学生1人分の探索記述子: 残り必要数と選択可能学期のビットマスク
+///
+/// `mask` は `u64` なので `config.term_count` は最大64までしか扱えない
+#[derive(PartialEq, Eq, Hash)]
+struct Descriptor {
+    required: [usize; 4],
+    mask: u64,
+}
+
+impl Descriptor {
+    fn of(student: &Student, config: &Config) -> Self {
+        let required = student.required_hospitals(config);
+        let mask = student
+            .selectable_terms()
+            .fold(0u64, |mask, term| mask | (1 << term));
+        Descriptor {
+            required: [
+                required.count(HospitalType::InnerMedical),
+                required.count(HospitalType::InnerSurgical),
+                required.count(HospitalType::OuterSurgical),
+                required.count(HospitalType::OuterMedical),
+            ],
+            mask,
+        }
+    }
+}
+
+/// 現在の探索状態を正規化したハッシュ値にする（学生の並び順には依存しない）
+fn canonical_key(table: &HospitalTableInner, students: &[Student], config: &Config) -> u64 {
+    let mut descriptors: Vec<Descriptor> = students
+        .iter()
+        .filter(|s| !s.done())
+        .map(|s| Descriptor::of(s, config))
+        .collect();
+    descriptors.sort_by_key(|d| (d.required, d.mask));
+
+    let mut hasher = DefaultHasher::new();
+    for slot in table {
+        [
+            slot.count(HospitalType::InnerMedical),
+            slot.count(HospitalType::InnerSurgical),
+            slot.count(HospitalType::OuterSurgical),
+            slot.count(HospitalType::OuterMedical),
+        ]
+        .hash(&mut hasher);
+    }
+    descriptors.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// バックトラッキングによる厳密な割当可能性の判定器。
+///
+/// 一度探索して実行不可能と確定した状態をキャッシュし、同じ状態への再訪を枝刈りする。
+#[derive(Default)]
+pub struct Solver {
+    dead_states: HashSet<u64>,
+}
+
+impl Solver {
+    pub fn new() -> Self {
+        Solver::default()
+    }
+
+    /// 全員を割り当てきれるか判定し、可能であれば1つの割当を返す
+    pub fn solve(
+        &mut self,
+        table: &HospitalTable,
+        students: &[Student],
+        config: &Config,
+    ) -> Option<Vec<Student>> {
+        let mut table = table.as_inner().clone();
+        let mut students: Vec<Student> = students.to_vec();
+        self.search(&mut table, &mut students, config)
+            .then_some(students)
+    }
+
+    fn search(
+        &mut self,
+        table: &mut HospitalTableInner,
+        students: &mut [Student],
+        config: &Config,
+    ) -> bool {
+        let pending: Vec<usize> = students
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.done())
+            .map(|(i, _)| i)
+            .collect();
+        if pending.is_empty() {
+            return true;
+        }
+
+        let key = canonical_key(table, students, config);
+        if self.dead_states.contains(&key) {
+            return false;
+        }
+
+        // MRV: 現時点で選択肢が最も少ない学生を優先して埋める
+        let (chosen, choices) = pending
+            .into_iter()
+            .map(|i| (i, Self::feasible_choices(table, &students[i], config)))
+            .min_by_key(|(_, choices)| choices.len())
+            .expect("pending is non-empty");
+
+        for (term, hospital) in choices {
+            table[term] -= hospital;
+            students[chosen].assign(term, hospital);
+
+            if self.search(table, students, config) {
+                return true;
+            }
+
+            students[chosen].unassign(term);
+            table[term] += hospital;
+        }
+
+        self.dead_states.insert(key);
+        false
+    }
+
+    /// `student` が次に置ける `(term, HospitalType)` の一覧
+    fn feasible_choices(
+        table: &HospitalTableInner,
+        student: &Student,
+        config: &Config,
+    ) -> Vec<(usize, HospitalType)> {
+        (&HospitalTable::new(table.clone()) & (student, config))
+            .iter_choices()
+            .map(|(term, hospital, _)| (term, hospital))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::CourseConfig,
+        eschaton::{InitStudentOption, TermVacants},
+    };
+
+    fn test_config() -> Config {
+        Config {
+            term_count: 6,
+            categories: Vec::new(),
+            courses: Vec::new(),
+            reserves_path: String::new(),
+            students_path: String::new(),
+        }
+    }
+
+    /// コース未設定（= required_hospitals が infinite() になる）学生は、
+    /// 各学期に何らかの空きさえあれば型を問わず埋まる
+    fn unconstrained_student(table: &mut HospitalTable, name: &str) -> Student {
+        table.init_student(InitStudentOption {
+            name: name.to_string(),
+            terms: vec![None; 6],
+        })
+    }
+
+    #[test]
+    fn solves_when_every_term_has_some_supply() {
+        let config = test_config();
+        let mut table = HospitalTable::new(vec![TermVacants::new(1, 0, 0, 0); 6]);
+        let student = unconstrained_student(&mut table, "Alice");
+        assert!(Solver::new().solve(&table, &[student], &config).is_some());
+    }
+
+    #[test]
+    fn fails_when_a_term_has_no_supply_at_all() {
+        let config = test_config();
+        let mut table = HospitalTable::new(vec![TermVacants::zero(); 6]);
+        let student = unconstrained_student(&mut table, "Bob");
+        assert!(Solver::new().solve(&table, &[student], &config).is_none());
+    }
+
+    /// 2人の学生が同じ学期・同じ残り枠を取り合い、どちらを先に割り当てるかによっては
+    /// 詰んでしまう（他方が必要数を満たせなくなる）が、全体としては両立する割当が1つだけ
+    /// 存在する状況を作る。dead_states によるメモ化や MRV による刈り込みが、この
+    /// 解ける分岐まで過剰に刈ってしまわないことを確認する
+    #[test]
+    fn backtracks_out_of_a_dead_end_to_find_the_only_valid_assignment() {
+        let config = Config {
+            term_count: 5,
+            categories: Vec::new(),
+            courses: vec![
+                CourseConfig {
+                    name: "InnerSurgical-tagged".to_string(),
+                    profile: TermVacants::new(0, 2, 0, 3),
+                },
+                CourseConfig {
+                    name: "OuterSurgical-tagged".to_string(),
+                    profile: TermVacants::new(1, 0, 2, 2),
+                },
+            ],
+            reserves_path: String::new(),
+            students_path: String::new(),
+        };
+        let mut table = HospitalTable::new(vec![
+            TermVacants::new(0, 1, 1, 0), // term0: 片方の学期タグ付け用の枠
+            TermVacants::new(0, 1, 1, 0), // term1: 同上
+            TermVacants::new(0, 0, 0, 2), // term2: OuterMedical が2つだけ
+            TermVacants::new(1, 0, 0, 2), // term3: InnerMedical が1つ、OuterMedical が2つ
+            TermVacants::new(1, 0, 0, 1), // term4: InnerMedical と OuterMedical が1つずつ
+        ]);
+        // 同じ病院種別を2回選ぶとコースが確定する既存の仕組みを使い、互いに異なる
+        // コースへ振り分ける（Alice: InnerSurgical を2回 → 残り OM3、Bob: OuterSurgical
+        // を2回 → 残り IM1/OM2）。残り3学期の枠を食い合う形になっており、先に割り当てる
+        // 学期・組み合わせを誤ると一方が詰む
+        let alice = table.init_student(InitStudentOption {
+            name: "Alice".to_string(),
+            terms: vec![
+                Some(HospitalType::InnerSurgical),
+                Some(HospitalType::InnerSurgical),
+                None,
+                None,
+                None,
+            ],
+        });
+        let bob = table.init_student(InitStudentOption {
+            name: "Bob".to_string(),
+            terms: vec![
+                Some(HospitalType::OuterSurgical),
+                Some(HospitalType::OuterSurgical),
+                None,
+                None,
+                None,
+            ],
+        });
+
+        let mut solver = Solver::new();
+        let solution = solver
+            .solve(&table, &[alice, bob], &config)
+            .expect("a valid assignment exists even though some branches dead-end");
+        for student in &solution {
+            assert!(student.done());
+        }
+        // 一発で正解にたどり着くのではなく、本当に行き詰まって引き返したことを確認する
+        assert!(
+            !solver.dead_states.is_empty(),
+            "this case is only interesting if at least one branch actually dead-ends"
+        );
+    }
+}